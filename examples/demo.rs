@@ -10,9 +10,308 @@ use tao::{
 use egui_wgpu::wgpu;
 use egui_wgpu::wgpu::InstanceDescriptor;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    Auto,
+    Immediate,
+    Mailbox,
+    Fifo,
+}
+
+impl PresentMode {
+    fn resolve(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let wanted = match self {
+            PresentMode::Auto => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+        };
+
+        if supported.contains(&wanted) {
+            wanted
+        } else {
+            wgpu::PresentMode::Fifo // guaranteed to be supported
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderingMode {
+    Srgb,
+    LinearNonSrgb,
+    HdrExtended { reference_white_nits: f32 },
+}
+
+impl RenderingMode {
+    fn select_format(self, formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+        match self {
+            RenderingMode::Srgb => formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb())
+                .unwrap_or(formats[0]),
+            RenderingMode::LinearNonSrgb => formats
+                .iter()
+                .copied()
+                .find(|f| !f.is_srgb())
+                .unwrap_or(formats[0]),
+            RenderingMode::HdrExtended { .. } => formats
+                .iter()
+                .copied()
+                .find(|f| *f == wgpu::TextureFormat::Rgba16Float)
+                .or_else(|| formats.iter().copied().find(|f| !f.is_srgb()))
+                .unwrap_or(formats[0]),
+        }
+    }
+
+    // Whether `format` actually satisfies this mode, so callers can tell a negotiated
+    // format apart from a silent fallback (e.g. Srgb requested but no sRGB format listed).
+    fn is_satisfied_by(self, format: wgpu::TextureFormat) -> bool {
+        match self {
+            RenderingMode::Srgb => format.is_srgb(),
+            RenderingMode::LinearNonSrgb => !format.is_srgb(),
+            RenderingMode::HdrExtended { .. } => format == wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+// egui_wgpu recommends dithering whenever the surface isn't sRGB. Derived from the
+// negotiated format, not the requested RenderingMode, since select_format can fall back
+// to a format the request didn't ask for.
+fn dithering_for(format: wgpu::TextureFormat) -> bool {
+    !format.is_srgb()
+}
+
+// Scales egui's 0-1 colors up to the configured reference white point before they hit an
+// Rgba16Float swapchain, so HdrExtended doesn't just ship SDR-authored colors unscaled.
+const TONEMAP_SHADER: &str = r#"
+struct Uniforms {
+    scale: f32,
+}
+
+@group(0) @binding(0) var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1) var hdr_sampler: sampler;
+@group(0) @binding(2) var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var positions = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0),
+    );
+    let pos = positions[index];
+    var out: VertexOutput;
+    out.position = vec4<f32>(pos, 0.0, 1.0);
+    out.uv = pos * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(hdr_texture, hdr_sampler, in.uv);
+    return vec4<f32>(color.rgb * uniforms.scale, color.a);
+}
+"#;
+
+struct Tonemap {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    scale_buffer: wgpu::Buffer,
+    offscreen_view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Tonemap {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        reference_white_nits: f32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("tonemap shader"),
+            source: wgpu::ShaderSource::Wgsl(TONEMAP_SHADER.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tonemap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("tonemap pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("tonemap sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let scale_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tonemap scale"),
+            size: 4,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(
+            &scale_buffer,
+            0,
+            &(reference_white_nits / 80.0).to_ne_bytes(),
+        );
+
+        let offscreen_view = Self::make_offscreen_view(device, format, width, height);
+        let bind_group = Self::make_bind_group(
+            device,
+            &bind_group_layout,
+            &offscreen_view,
+            &sampler,
+            &scale_buffer,
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            scale_buffer,
+            offscreen_view,
+            bind_group,
+        }
+    }
+
+    fn make_offscreen_view(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> wgpu::TextureView {
+        let offscreen = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("tonemap offscreen"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        offscreen.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn make_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        offscreen_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        scale_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tonemap bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(offscreen_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: scale_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) {
+        self.offscreen_view = Self::make_offscreen_view(device, format, width, height);
+        self.bind_group = Self::make_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.offscreen_view,
+            &self.sampler,
+            &self.scale_buffer,
+        );
+    }
+}
+
 pub struct Renderer {
     gpu: Gpu,
     egui_renderer: egui_wgpu::Renderer,
+    rendering_mode: RenderingMode,
+    tonemap: Option<Tonemap>,
+    last_frame_time: Duration,
 }
 
 impl Renderer {
@@ -20,17 +319,83 @@ impl Renderer {
         window: impl Into<wgpu::SurfaceTarget<'static>>,
         width: u32,
         height: u32,
+        present_mode: PresentMode,
+        rendering_mode: RenderingMode,
     ) -> Self {
-        let gpu = Gpu::new_async(window, width, height).await;
+        let gpu = Gpu::new_async(window, width, height, present_mode, rendering_mode).await;
 
-        let egui_renderer =
-            egui_wgpu::Renderer::new(&gpu.device, gpu.surface_config.format, None, 1, false);
+        let egui_renderer = egui_wgpu::Renderer::new(
+            &gpu.device,
+            gpu.surface_config.format,
+            None,
+            1,
+            dithering_for(gpu.surface_format),
+        );
 
-        Self { gpu, egui_renderer }
+        let tonemap = if let RenderingMode::HdrExtended {
+            reference_white_nits,
+        } = rendering_mode
+        {
+            Some(Tonemap::new(
+                &gpu.device,
+                &gpu.queue,
+                gpu.surface_format,
+                width,
+                height,
+                reference_white_nits,
+            ))
+        } else {
+            None
+        };
+
+        Self {
+            gpu,
+            egui_renderer,
+            rendering_mode,
+            tonemap,
+            last_frame_time: Duration::ZERO,
+        }
+    }
+
+    pub fn last_frame_time(&self) -> Duration {
+        self.last_frame_time
+    }
+
+    pub fn supported_rendering_modes(&self) -> Vec<RenderingMode> {
+        self.gpu.supported_rendering_modes()
+    }
+
+    pub fn reference_white_nits(&self) -> Option<f32> {
+        match self.rendering_mode {
+            RenderingMode::HdrExtended {
+                reference_white_nits,
+            } => Some(reference_white_nits),
+            _ => None,
+        }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         self.gpu.resize(width, height);
+        if let Some(tonemap) = &mut self.tonemap {
+            tonemap.resize(&self.gpu.device, self.gpu.surface_format, width, height);
+        }
+    }
+
+    pub fn set_vsync(&mut self, enabled: bool) {
+        self.gpu.set_present_mode(if enabled {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        });
+    }
+
+    pub fn register_scene_texture(
+        &mut self,
+        texture_view: &wgpu::TextureView,
+        filter: wgpu::FilterMode,
+    ) -> egui::TextureId {
+        self.egui_renderer
+            .register_native_texture(&self.gpu.device, texture_view, filter)
     }
 
     pub fn render_frame(
@@ -38,8 +403,11 @@ impl Renderer {
         screen_descriptor: egui_wgpu::ScreenDescriptor,
         paint_jobs: Vec<egui::epaint::ClippedPrimitive>,
         textures_delta: egui::TexturesDelta,
-        _delta_time: crate::Duration,
+        delta_time: Duration,
+        scene: Option<&mut dyn FnMut(&mut wgpu::CommandEncoder, &wgpu::TextureView)>,
     ) {
+        self.last_frame_time = delta_time;
+
         for (id, image_delta) in &textures_delta.set {
             self.egui_renderer
                 .update_texture(&self.gpu.device, &self.gpu.queue, *id, image_delta);
@@ -87,15 +455,33 @@ impl Renderer {
 
         encoder.insert_debug_marker("Render scene");
 
+        let scene_drawn = scene.is_some();
+        if let Some(scene) = scene {
+            scene(&mut encoder, &surface_texture_view);
+        }
+
+        // Without a tonemap pass egui renders straight onto the surface, loading over a
+        // scene if one was drawn. With one, egui renders into the offscreen target instead
+        // and the tonemap pass composites it onto the surface afterwards.
+        let egui_target_view = match &self.tonemap {
+            Some(tonemap) => &tonemap.offscreen_view,
+            None => &surface_texture_view,
+        };
+        let egui_load_op = if self.tonemap.is_some() || !scene_drawn {
+            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+        } else {
+            wgpu::LoadOp::Load
+        };
+
         // need this block to preserve encoder ownership
         {
             let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &surface_texture_view,
+                    view: egui_target_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        load: egui_load_op,
                         store: wgpu::StoreOp::Store,
                     },
                 })],
@@ -111,6 +497,30 @@ impl Renderer {
             );
         }
 
+        if let Some(tonemap) = &self.tonemap {
+            let mut tonemap_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Tonemap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_texture_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: if scene_drawn {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                        },
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            tonemap_pass.set_pipeline(&tonemap.pipeline);
+            tonemap_pass.set_bind_group(0, &tonemap.bind_group, &[]);
+            tonemap_pass.draw(0..3, 0..1);
+        }
+
         self.gpu.queue.submit(std::iter::once(encoder.finish()));
         surface_texture.present();
     }
@@ -122,6 +532,8 @@ pub struct Gpu {
     pub queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub surface_format: wgpu::TextureFormat,
+    supported_present_modes: Vec<wgpu::PresentMode>,
+    supported_formats: Vec<wgpu::TextureFormat>,
 }
 
 impl Gpu {
@@ -135,10 +547,36 @@ impl Gpu {
         self.surface.configure(&self.device, &self.surface_config);
     }
 
+    pub fn set_present_mode(&mut self, mode: PresentMode) {
+        self.surface_config.present_mode = mode.resolve(&self.supported_present_modes);
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
+    pub fn supported_rendering_modes(&self) -> Vec<RenderingMode> {
+        let mut modes = Vec::new();
+        if self.supported_formats.iter().any(|f| f.is_srgb()) {
+            modes.push(RenderingMode::Srgb);
+        }
+        if self.supported_formats.iter().any(|f| !f.is_srgb()) {
+            modes.push(RenderingMode::LinearNonSrgb);
+        }
+        if self
+            .supported_formats
+            .contains(&wgpu::TextureFormat::Rgba16Float)
+        {
+            modes.push(RenderingMode::HdrExtended {
+                reference_white_nits: 203.0, // SDR reference white, ITU-R BT.2408
+            });
+        }
+        modes
+    }
+
     pub async fn new_async(
         window: impl Into<wgpu::SurfaceTarget<'static>>,
         width: u32,
         height: u32,
+        present_mode: PresentMode,
+        rendering_mode: RenderingMode,
     ) -> Self {
         let instance = wgpu::Instance::new(&InstanceDescriptor::default());
         let surface = instance.create_surface(window).unwrap();
@@ -169,19 +607,22 @@ impl Gpu {
 
         let surface_capabilities = surface.get_capabilities(&adapter);
 
-        let surface_format = surface_capabilities
-            .formats
-            .iter()
-            .copied()
-            .find(|f| !f.is_srgb()) // egui wants a non-srgb surface texture
-            .unwrap_or(surface_capabilities.formats[0]);
+        let surface_format = rendering_mode.select_format(&surface_capabilities.formats);
+        if !rendering_mode.is_satisfied_by(surface_format) {
+            println!(
+                "Warning: adapter doesn't support {rendering_mode:?}; falling back to {surface_format:?}"
+            );
+        }
+
+        let supported_present_modes = surface_capabilities.present_modes.clone();
+        let supported_formats = surface_capabilities.formats.clone();
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width,
             height,
-            present_mode: surface_capabilities.present_modes[0],
+            present_mode: present_mode.resolve(&supported_present_modes),
             alpha_mode: surface_capabilities.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
@@ -194,6 +635,8 @@ impl Gpu {
             device,
             queue,
             surface_config,
+            supported_present_modes,
+            supported_formats,
             surface_format,
         }
     }
@@ -221,6 +664,10 @@ impl DemoApp {
         let last_size = (inner_size.width, inner_size.height);
 
         let viewport_id = gui_context.viewport_id();
+        // BLOCKED: multi-viewport (spawning/tracking child tao windows per ViewportId,
+        // routing WindowEvents by WindowId) has to live in egui_tao::State itself, and
+        // that crate's source isn't part of this checkout, so it can't be implemented
+        // here. This demo still only drives the root viewport.
         let gui_state = egui_tao::State::new(
             gui_context,
             viewport_id,
@@ -234,8 +681,16 @@ impl DemoApp {
 
         let window_handle = Arc::new(window);
 
-        let renderer =
-            pollster::block_on(async { Renderer::new(window_handle.clone(), width, height).await });
+        let renderer = pollster::block_on(async {
+            Renderer::new(
+                window_handle.clone(),
+                width,
+                height,
+                PresentMode::Auto,
+                RenderingMode::Srgb,
+            )
+            .await
+        });
 
         Self {
             window: window_handle,
@@ -250,7 +705,10 @@ impl DemoApp {
     fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
         match event {
             Event::WindowEvent { event, .. } => {
-                // Receive gui window event
+                // BLOCKED: translating tao's IME events to egui::Event::Ime (and honoring
+                // PlatformOutput::ime) has to happen inside egui_tao::State::on_window_event,
+                // and that crate's source isn't part of this checkout, so it can't be
+                // implemented here. CJK/dead-key composition in any TextEdit is broken.
                 if self
                     .gui_state
                     .on_window_event(&self.window, &event)
@@ -300,6 +758,7 @@ impl DemoApp {
                     shapes,
                     pixels_per_point,
                     platform_output,
+                    viewport_output,
                     ..
                 } = self.gui_state.egui_ctx().end_pass();
 
@@ -324,12 +783,37 @@ impl DemoApp {
                     paint_jobs,
                     textures_delta,
                     delta_time,
+                    None,
                 );
+
+                // Sleep until egui has something new to paint instead of busy-polling.
+                let repaint_delay = viewport_output
+                    .values()
+                    .map(|output| output.repaint_delay)
+                    .min()
+                    .unwrap_or(Duration::MAX)
+                    .saturating_sub(self.renderer.last_frame_time());
+                *control_flow = Self::control_flow_for(repaint_delay);
+            }
+            Event::MainEventsCleared => {
                 self.window.request_redraw();
             }
             _ => (),
         }
     }
+
+    // BLOCKED: the request asks for this as State::control_flow_for (part of egui_tao),
+    // same crate-boundary problem as the multi-viewport/IME requests — egui_tao's source
+    // isn't part of this checkout, so it's implemented here at the demo layer instead.
+    fn control_flow_for(repaint_delay: Duration) -> ControlFlow {
+        if repaint_delay.is_zero() {
+            ControlFlow::Poll
+        } else if let Some(deadline) = Instant::now().checked_add(repaint_delay) {
+            ControlFlow::WaitUntil(deadline)
+        } else {
+            ControlFlow::Wait
+        }
+    }
 }
 
 fn main() {
@@ -339,7 +823,6 @@ fn main() {
     app.window.request_redraw();
 
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
         app.handle_event(event, control_flow);
     });
 }